@@ -46,9 +46,18 @@ pub struct StackRecognizer<S: Copy, R: FunctionalRecognizer<S>> {
     stack_ptr: usize,
 }
 
+/// Initial stack depth for [`StackRecognizer::from`]. Deep grammars grow past
+/// this via [`StackRecognizer::try_push_byte`]; callers who know their depth
+/// up front should use [`StackRecognizer::with_capacity`] instead.
+const DEFAULT_STACK_CAPACITY: usize = 300;
+
 impl<S: Copy, R: FunctionalRecognizer<S>> StackRecognizer<S, R> {
     pub fn from(rec: R) -> Self {
-        let stack = vec![rec.initial(); 300];
+        Self::with_capacity(rec, DEFAULT_STACK_CAPACITY)
+    }
+
+    pub fn with_capacity(rec: R, capacity: usize) -> Self {
+        let stack = vec![rec.initial(); capacity.max(1)];
         StackRecognizer {
             rec,
             stack,
@@ -103,6 +112,9 @@ impl<S: Copy + Debug, R: FunctionalRecognizer<S>> Recognizer for StackRecognizer
         match self.rec.try_append(self.stack[self.stack_ptr], byte) {
             Some(state) => {
                 self.stack_ptr += 1;
+                if self.stack_ptr == self.stack.len() {
+                    self.grow();
+                }
                 self.stack[self.stack_ptr] = state;
                 true
             }
@@ -111,6 +123,16 @@ impl<S: Copy + Debug, R: FunctionalRecognizer<S>> Recognizer for StackRecognizer
     }
 }
 
+impl<S: Copy, R: FunctionalRecognizer<S>> StackRecognizer<S, R> {
+    /// Double the stack's capacity. Split out of `try_push_byte` so the
+    /// common (no-growth) path stays a single bounds check away from the
+    /// inlined fast path.
+    #[cold]
+    fn grow(&mut self) {
+        self.stack.resize(self.stack.len() * 2, self.rec.initial());
+    }
+}
+
 #[derive(Clone)]
 pub struct AnythingGoes {}
 
@@ -127,3 +149,291 @@ impl FunctionalRecognizer<()> for AnythingGoes {
         true
     }
 }
+
+/// Intersection of two recognizers: a byte is accepted only if both `A` and
+/// `B` accept it, and a special token is allowed only if both allow it.
+#[derive(Clone)]
+pub struct And<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> And<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        And { a, b }
+    }
+}
+
+impl<SA: Copy, SB: Copy, A: FunctionalRecognizer<SA>, B: FunctionalRecognizer<SB>>
+    FunctionalRecognizer<(SA, SB)> for And<A, B>
+{
+    fn initial(&self) -> (SA, SB) {
+        (self.a.initial(), self.b.initial())
+    }
+
+    fn try_append(&self, state: (SA, SB), byte: u8) -> Option<(SA, SB)> {
+        let (sa, sb) = state;
+        let sa = self.a.try_append(sa, byte)?;
+        let sb = self.b.try_append(sb, byte)?;
+        Some((sa, sb))
+    }
+
+    fn special_allowed(&self, state: (SA, SB), tok: SpecialToken) -> bool {
+        self.a.special_allowed(state.0, tok) && self.b.special_allowed(state.1, tok)
+    }
+}
+
+/// Union of two recognizers: a byte is accepted if either `A` or `B` still
+/// accepts it. Each branch carries `None` once it has rejected a byte (its
+/// "dead" sentinel), and the whole thing dies only once both branches have.
+#[derive(Clone)]
+pub struct Or<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Or<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Or { a, b }
+    }
+}
+
+impl<SA: Copy, SB: Copy, A: FunctionalRecognizer<SA>, B: FunctionalRecognizer<SB>>
+    FunctionalRecognizer<(Option<SA>, Option<SB>)> for Or<A, B>
+{
+    fn initial(&self) -> (Option<SA>, Option<SB>) {
+        (Some(self.a.initial()), Some(self.b.initial()))
+    }
+
+    fn try_append(
+        &self,
+        state: (Option<SA>, Option<SB>),
+        byte: u8,
+    ) -> Option<(Option<SA>, Option<SB>)> {
+        let (sa, sb) = state;
+        let sa = sa.and_then(|s| self.a.try_append(s, byte));
+        let sb = sb.and_then(|s| self.b.try_append(s, byte));
+        if sa.is_none() && sb.is_none() {
+            None
+        } else {
+            Some((sa, sb))
+        }
+    }
+
+    fn special_allowed(&self, state: (Option<SA>, Option<SB>), tok: SpecialToken) -> bool {
+        let (sa, sb) = state;
+        sa.map(|s| self.a.special_allowed(s, tok)).unwrap_or(false)
+            || sb.map(|s| self.b.special_allowed(s, tok)).unwrap_or(false)
+    }
+}
+
+/// State for [`Then`]: either still driving `A`, or handed off to `B`.
+#[derive(Clone, Copy, Debug)]
+pub enum ThenState<SA, SB> {
+    First(SA),
+    Second(SB),
+}
+
+/// Sequencing of two recognizers: bytes drive `A` until it can no longer
+/// accept them, at which point (provided `A` was willing to end there, i.e.
+/// allows [`SpecialToken::EndOfSentence`]) the same byte is replayed against
+/// a fresh `B`. Once handed off, `B` drives everything that follows.
+///
+/// This is maximal-munch, not earliest-match: `A` is greedily extended for
+/// as long as it keeps accepting, and handoff only happens once it rejects a
+/// byte outright. If `A` and `B` overlap (e.g. `A` accepts `"a"` or `"ab"`
+/// and `B` accepts `"bc"`), a valid split earlier than `A`'s longest match
+/// can be missed — for input `"abc"`, `A` greedily consumes `"ab"`, replays
+/// `'c'` against a fresh `B`, and rejects it, even though `"a"` then `"bc"`
+/// is a valid split. Recognizers intended for use with `Then` should not
+/// rely on the shorter match also being accepted at a longer prefix.
+#[derive(Clone)]
+pub struct Then<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Then<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Then { a, b }
+    }
+}
+
+impl<SA: Copy, SB: Copy, A: FunctionalRecognizer<SA>, B: FunctionalRecognizer<SB>>
+    FunctionalRecognizer<ThenState<SA, SB>> for Then<A, B>
+{
+    fn initial(&self) -> ThenState<SA, SB> {
+        ThenState::First(self.a.initial())
+    }
+
+    fn try_append(&self, state: ThenState<SA, SB>, byte: u8) -> Option<ThenState<SA, SB>> {
+        match state {
+            ThenState::First(sa) => {
+                if let Some(sa) = self.a.try_append(sa, byte) {
+                    return Some(ThenState::First(sa));
+                }
+                if self.a.special_allowed(sa, SpecialToken::EndOfSentence) {
+                    let sb = self.b.initial();
+                    self.b.try_append(sb, byte).map(ThenState::Second)
+                } else {
+                    None
+                }
+            }
+            ThenState::Second(sb) => self.b.try_append(sb, byte).map(ThenState::Second),
+        }
+    }
+
+    fn special_allowed(&self, state: ThenState<SA, SB>, tok: SpecialToken) -> bool {
+        match state {
+            // Stopping while still in `A` only makes sense if `B` could
+            // also stop right away (i.e. `B` accepts zero bytes); otherwise
+            // `A` being willing to end just means the handoff byte is about
+            // to be replayed against `B`, not that the whole sequence is done.
+            ThenState::First(sa) if matches!(tok, SpecialToken::EndOfSentence) => {
+                self.a.special_allowed(sa, tok) && self.b.special_allowed(self.b.initial(), tok)
+            }
+            ThenState::First(sa) => self.a.special_allowed(sa, tok),
+            ThenState::Second(sb) => self.b.special_allowed(sb, tok),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recognizer accepting exactly the literal byte string `needle`,
+    /// willing to stop only once it has consumed all of it.
+    #[derive(Clone)]
+    struct Literal {
+        needle: &'static [u8],
+    }
+
+    impl FunctionalRecognizer<usize> for Literal {
+        fn initial(&self) -> usize {
+            0
+        }
+
+        fn try_append(&self, state: usize, byte: u8) -> Option<usize> {
+            if state < self.needle.len() && self.needle[state] == byte {
+                Some(state + 1)
+            } else {
+                None
+            }
+        }
+
+        fn special_allowed(&self, state: usize, tok: SpecialToken) -> bool {
+            match tok {
+                SpecialToken::EndOfSentence => state == self.needle.len(),
+            }
+        }
+    }
+
+    fn feed<S: Copy, R: FunctionalRecognizer<S>>(rec: &R, bytes: &[u8]) -> Option<S> {
+        let mut state = rec.initial();
+        for &b in bytes {
+            state = rec.try_append(state, b)?;
+        }
+        Some(state)
+    }
+
+    #[test]
+    fn stack_recognizer_grows_past_default_capacity() {
+        // Regression test: before the stack grew geometrically, pushing a
+        // byte once `stack_ptr` reached `DEFAULT_STACK_CAPACITY` indexed
+        // straight past the end of the fixed-size `Vec` and panicked.
+        let mut rec = StackRecognizer::from(AnythingGoes {});
+        for depth in 0..(DEFAULT_STACK_CAPACITY * 3) {
+            assert!(rec.try_push_byte(b'x'));
+            assert_eq!(rec.stack_ptr, depth + 1);
+        }
+    }
+
+    #[test]
+    fn and_requires_both_branches_to_accept() {
+        let rec = And::new(Literal { needle: b"ab" }, Literal { needle: b"ab" });
+        assert!(feed(&rec, b"ab").is_some());
+        assert!(feed(&rec, b"ac").is_none());
+
+        let mismatched = And::new(Literal { needle: b"ab" }, Literal { needle: b"ac" });
+        assert!(feed(&mismatched, b"ab").is_none());
+    }
+
+    #[test]
+    fn or_accepts_if_either_branch_accepts() {
+        let rec = Or::new(Literal { needle: b"ab" }, Literal { needle: b"ac" });
+        let sa = feed(&rec, b"ab").expect("accepted by a");
+        assert!(rec.special_allowed(sa, SpecialToken::EndOfSentence));
+
+        let sb = feed(&rec, b"ac").expect("accepted by b");
+        assert!(rec.special_allowed(sb, SpecialToken::EndOfSentence));
+
+        assert!(feed(&rec, b"ad").is_none());
+    }
+
+    #[test]
+    fn then_sequences_a_then_b() {
+        let rec = Then::new(Literal { needle: b"ab" }, Literal { needle: b"cd" });
+        let state = feed(&rec, b"abcd").expect("a then b accepted");
+        assert!(rec.special_allowed(state, SpecialToken::EndOfSentence));
+
+        // `a` alone can't stop: `b` hasn't consumed anything yet.
+        let mid = feed(&rec, b"ab").expect("a alone is a valid prefix");
+        assert!(!rec.special_allowed(mid, SpecialToken::EndOfSentence));
+    }
+
+    #[test]
+    fn then_is_drivable_through_stack_recognizer() {
+        // Regression test: `ThenState` previously derived only `Clone, Copy`,
+        // but `StackRecognizer` requires `S: Copy + Debug`, so this
+        // combination (the whole point of `And`/`Or`/`Then` being
+        // `FunctionalRecognizer`s) failed to compile.
+        let mut rec = StackRecognizer::from(Then::new(
+            Literal { needle: b"ab" },
+            Literal { needle: b"cd" },
+        ));
+        for byte in b"abcd" {
+            assert!(rec.try_push_byte(*byte));
+        }
+        assert!(rec.special_allowed(SpecialToken::EndOfSentence));
+    }
+
+    #[test]
+    fn then_is_greedy_and_may_reject_a_valid_split() {
+        // Documents the maximal-munch limitation noted on `Then`: `a`
+        // accepts "a" or "ab", `b` accepts "bc". The only valid split of
+        // "abc" is "a" | "bc", but `a` greedily consumes "ab" first and then
+        // "c" alone isn't accepted by a fresh `b`.
+        struct AOrAb;
+        impl FunctionalRecognizer<usize> for AOrAb {
+            fn initial(&self) -> usize {
+                0
+            }
+            fn try_append(&self, state: usize, byte: u8) -> Option<usize> {
+                match (state, byte) {
+                    (0, b'a') => Some(1),
+                    (1, b'b') => Some(2),
+                    _ => None,
+                }
+            }
+            fn special_allowed(&self, state: usize, tok: SpecialToken) -> bool {
+                match tok {
+                    SpecialToken::EndOfSentence => state == 1 || state == 2,
+                }
+            }
+        }
+
+        let rec = Then::new(AOrAb, Literal { needle: b"bc" });
+        assert!(feed::<ThenState<usize, usize>, _>(&rec, b"abc").is_none());
+    }
+
+    #[test]
+    fn then_does_not_allow_early_end_of_sentence() {
+        // `a` is vacuously complete from the start (empty needle), but `b`
+        // requires at least one byte, so ending before handing off to `b`
+        // must still be rejected.
+        let rec = Then::new(Literal { needle: b"" }, Literal { needle: b"b" });
+        let state: ThenState<usize, usize> = rec.initial();
+        assert!(!rec.special_allowed(state, SpecialToken::EndOfSentence));
+    }
+}
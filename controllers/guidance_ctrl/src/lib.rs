@@ -17,11 +17,23 @@ macro_rules! infoln {
     };
 }
 
+/// Diagnostic recorded when `TokenParser` has to recover from a rejected
+/// byte instead of hard-panicking (see `set_recover_on_reject`).
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    pub byte_offset: usize,
+    pub rejected_byte: u8,
+}
+
 pub struct TokenParser {
     toktrie: TokTrie,
     pub parser: Parser,
     // tokens currently in KV cache
     llm_tokens: Vec<TokenId>,
+    // panic-mode recovery: if true, a rejected byte in the scan loop is
+    // recorded in `scan_errors` and skipped instead of panicking
+    recover_on_reject: bool,
+    scan_errors: Vec<ScanError>,
 }
 
 impl TokenParser {
@@ -36,9 +48,23 @@ impl TokenParser {
             toktrie: TokTrie::from_host(),
             parser,
             llm_tokens: Vec::new(),
+            recover_on_reject: false,
+            scan_errors: Vec::new(),
         })
     }
 
+    /// Enable panic-mode recovery: a byte rejected in `mid_process`'s scan
+    /// loop is recorded (see `take_scan_errors`) and skipped instead of
+    /// aborting the whole process.
+    pub fn set_recover_on_reject(&mut self, recover: bool) {
+        self.recover_on_reject = recover;
+    }
+
+    /// Drain the scan errors collected since the last call.
+    pub fn take_scan_errors(&mut self) -> Vec<ScanError> {
+        std::mem::take(&mut self.scan_errors)
+    }
+
     pub fn mid_process(&mut self, arg: MidProcessArg) -> MidProcessResult {
         infoln!("post tokens: {}", self.toktrie.tokens_dbg(&arg.tokens));
         arg.save_tokens(&mut self.llm_tokens);
@@ -99,9 +125,22 @@ impl TokenParser {
                 );
             }
 
-            for b in &llm_suffix[grm_suffix.len()..] {
+            for (offset, b) in llm_suffix[grm_suffix.len()..].iter().enumerate() {
                 let r = self.parser.scan(*b);
                 if r == ParseResult::Reject {
+                    if self.recover_on_reject {
+                        infoln!("recovered from rejected byte: {}", b);
+                        self.scan_errors.push(ScanError {
+                            // `llm_suffix` starts where `grm_suffix` does, and
+                            // the scanned bytes start `grm_suffix.len()` into
+                            // it, so that length has to be added back in, or
+                            // every offset here is short by `grm_suffix.len()`
+                            // (== chop_bytes).
+                            byte_offset: full_grm_bytes.len() - chop_bytes + grm_suffix.len() + offset,
+                            rejected_byte: *b,
+                        });
+                        continue;
+                    }
                     panic!("rejected byte: {}", b);
                 }
             }
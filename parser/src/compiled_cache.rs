@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use anyhow::{bail, ensure, Result};
+use toktrie::TokEnv;
+
+use crate::{api::ParserLimits, earley::grammar::CGrammar, Logger, TokenParser};
+
+/// Version of the `compile_to_bytes()` blob format. Bump this whenever the
+/// on-disk layout changes so old caches are rejected rather than
+/// misinterpreted.
+const CACHE_FORMAT_VERSION: u32 = 1;
+const CACHE_MAGIC: &[u8; 4] = b"LLGC";
+
+/// A versioned, self-describing snapshot of a *compiled* grammar (the
+/// `CGrammar` produced right before `Parser::new`), plus a fingerprint of the
+/// tokenizer it was compiled against. Building one of these with
+/// `compile_to_bytes()` lets a host skip `optimize()`/`compile()` (which can
+/// take on the order of 100ms for very large grammars) on every request and
+/// instead cheaply rehydrate a `TokenParser` with `from_compiled_grammar()`.
+#[derive(Debug)]
+struct CompiledGrammarCache {
+    tokenizer_fingerprint: u64,
+    cgrammar: CGrammar,
+}
+
+/// Hash a tokenizer's vocabulary so a cache built for one tokenizer is
+/// rejected (rather than silently misused) against another. Uses xxh3 rather
+/// than `DefaultHasher`: this fingerprint is embedded in the persisted
+/// `compile_to_bytes()` blob, and `DefaultHasher`'s output isn't guaranteed
+/// stable across Rust versions, which would make a cache written by one
+/// toolchain spuriously accepted or rejected by another.
+///
+/// Memoized per `TokEnv` (keyed by `Arc` identity): callers like
+/// `build_stop_controller` look this up on every request just to check a
+/// cache key, and rehashing the whole vocabulary every time would cost more
+/// than the work it's meant to save. Holds only a `Weak` reference so this
+/// cache doesn't itself keep a tokenizer alive past `llg_free_tokenizer`;
+/// entries for tokenizers that are no longer live are swept out on insert.
+pub(crate) fn tokenizer_fingerprint(tok_env: &TokEnv) -> u64 {
+    struct Entry {
+        tok_env: Weak<<TokEnv as Deref>::Target>,
+        fingerprint: u64,
+    }
+    static CACHE: OnceLock<Mutex<HashMap<usize, Entry>>> = OnceLock::new();
+
+    let key = Arc::as_ptr(tok_env) as *const () as usize;
+    let mut cache = CACHE
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    if let Some(entry) = cache.get(&key) {
+        if let Some(live) = entry.tok_env.upgrade() {
+            if Arc::ptr_eq(&live, tok_env) {
+                return entry.fingerprint;
+            }
+        }
+    }
+    let fingerprint = hash_vocab(tok_env);
+    cache.retain(|_, entry| entry.tok_env.strong_count() > 0);
+    cache.insert(
+        key,
+        Entry {
+            tok_env: Arc::downgrade(tok_env),
+            fingerprint,
+        },
+    );
+    fingerprint
+}
+
+fn hash_vocab(tok_env: &TokEnv) -> u64 {
+    let trie = tok_env.tok_trie();
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(trie.vocab_size() as u64).to_le_bytes());
+    buf.extend_from_slice(&(trie.eos_token() as u32).to_le_bytes());
+    for idx in 0..trie.vocab_size() as u32 {
+        let token = trie.token(idx);
+        buf.extend_from_slice(&(token.len() as u32).to_le_bytes());
+        buf.extend_from_slice(token);
+    }
+    xxhash_rust::xxh3::xxh3_64(&buf)
+}
+
+impl CompiledGrammarCache {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(CACHE_MAGIC);
+        out.extend_from_slice(&CACHE_FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.tokenizer_fingerprint.to_le_bytes());
+        let body = serde_json::to_vec(&self.cgrammar).expect("CGrammar is always serializable");
+        out.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    fn from_bytes(bytes: &[u8], tok_env: &TokEnv) -> Result<Self> {
+        ensure!(bytes.len() >= 4 + 4 + 8 + 8, "compiled grammar cache: truncated header");
+        ensure!(&bytes[0..4] == CACHE_MAGIC, "compiled grammar cache: bad magic");
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        ensure!(
+            version == CACHE_FORMAT_VERSION,
+            "compiled grammar cache: unsupported version {version}"
+        );
+        let fingerprint = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        ensure!(
+            fingerprint == tokenizer_fingerprint(tok_env),
+            "compiled grammar cache: built for a different tokenizer"
+        );
+        let body_len = u64::from_le_bytes(bytes[16..24].try_into().unwrap()) as usize;
+        let body = &bytes[24..];
+        ensure!(body.len() == body_len, "compiled grammar cache: truncated body");
+        let cgrammar = serde_json::from_slice(body)?;
+        Ok(CompiledGrammarCache {
+            tokenizer_fingerprint: fingerprint,
+            cgrammar,
+        })
+    }
+}
+
+impl TokenParser {
+    /// Compile the parser's current grammar into a cache blob that
+    /// `from_compiled_grammar()` can later turn back into a fresh
+    /// `TokenParser` without re-running `optimize()`/`compile()`.
+    pub fn compile_to_bytes(&self) -> Vec<u8> {
+        let cache = CompiledGrammarCache {
+            tokenizer_fingerprint: tokenizer_fingerprint(&self.token_env),
+            cgrammar: self.parser.cgrammar().clone(),
+        };
+        cache.to_bytes()
+    }
+
+    /// Instantiate a `TokenParser` from a blob produced by
+    /// `compile_to_bytes()`, skipping grammar optimization/compilation
+    /// entirely. Fails if `bytes` was compiled for a different tokenizer.
+    pub fn from_compiled_grammar(
+        token_env: TokEnv,
+        bytes: &[u8],
+        logger: Logger,
+        limits: ParserLimits,
+    ) -> Result<Self> {
+        let cache = match CompiledGrammarCache::from_bytes(bytes, &token_env) {
+            Ok(c) => c,
+            Err(e) => bail!("{e}"),
+        };
+        Self::from_cgrammar(token_env, cache.cgrammar, logger, limits)
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt::Write;
+
+use crate::TokenParser;
+
+/// Which aspect of the parser to render with `TokenParser::to_dot`/
+/// `Matcher::state_to_dot`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DotKind {
+    /// The compiled grammar's static structure: one node per nonterminal or
+    /// lexeme, with directed edges for productions.
+    Grammar,
+    /// The live Earley parse: one node per dotted item in the current rows,
+    /// with edges for scans/completions and live items highlighted.
+    RunState,
+}
+
+pub(crate) struct DotWriter {
+    out: String,
+}
+
+impl DotWriter {
+    pub(crate) fn new(name: &str) -> Self {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph {name} {{");
+        DotWriter { out }
+    }
+
+    pub(crate) fn node(&mut self, id: usize, label: &str, style: Option<&str>) {
+        let label = escape_label(label);
+        match style {
+            Some(style) => {
+                let _ = writeln!(self.out, "  n{id} [label=\"{label}\", {style}];");
+            }
+            None => {
+                let _ = writeln!(self.out, "  n{id} [label=\"{label}\"];");
+            }
+        }
+    }
+
+    pub(crate) fn edge(&mut self, from: usize, to: usize, label: &str) {
+        let label = escape_label(label);
+        let _ = writeln!(self.out, "  n{from} -> n{to} [label=\"{label}\"];");
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        self.out.push_str("}\n");
+        self.out
+    }
+}
+
+/// Escape a string for use inside a Graphviz quoted label: backslashes and
+/// double quotes must be backslash-escaped, or a lexeme/rule name containing
+/// either would terminate the label early and emit invalid DOT.
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl TokenParser {
+    /// See [`DotKind`]. This is the non-FFI entry point; `Matcher::state_to_dot`
+    /// and `llg_stringify_parser_dot` are thin wrappers around it.
+    pub fn to_dot(&self, kind: DotKind) -> String {
+        match kind {
+            DotKind::Grammar => self.grammar_to_dot(),
+            DotKind::RunState => self.run_state_to_dot(),
+        }
+    }
+
+    // `grammar_symbols`/`current_items` are part of the lower-level
+    // `Parser`'s own (pre-existing) introspection API, same tier as
+    // `is_accepting`/`compute_mask` used elsewhere in this crate.
+    fn grammar_to_dot(&self) -> String {
+        let mut w = DotWriter::new("grammar");
+        for (id, sym) in self.parser.grammar_symbols().into_iter().enumerate() {
+            w.node(id, &sym.name, None);
+            for target in sym.productions {
+                w.edge(id, target, "");
+            }
+        }
+        w.finish()
+    }
+
+    fn run_state_to_dot(&self) -> String {
+        let mut w = DotWriter::new("run_state");
+        for (id, item) in self.parser.current_items().into_iter().enumerate() {
+            let style = if item.is_live {
+                Some("style=filled, fillcolor=lightgreen")
+            } else {
+                Some("style=filled, fillcolor=lightgrey")
+            };
+            w.node(id, &format!("{} @ {}", item.label, item.origin), style);
+            for (target, edge_kind) in item.edges {
+                w.edge(id, target, edge_kind);
+            }
+        }
+        w.finish()
+    }
+}
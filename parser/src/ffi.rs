@@ -4,7 +4,7 @@ use std::{
     sync::Arc,
 };
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
 use toktrie::{InferenceCapabilities, TokEnv, TokRxInfo, TokTrie, TokenizerEnv};
 
 use crate::{
@@ -211,6 +211,12 @@ pub struct LlgConstraintInit {
     /// The resource limits for the parser
     /// Default values will be used for all fields that are 0
     pub limits: ParserLimits,
+    /// If true, `llg_new_constraint_any_for_prompt()` pops trailing prompt
+    /// tokens that could be a strict prefix of a longer vocabulary token and
+    /// forces the grammar to start with those bytes, instead of leaving the
+    /// prompt/generation boundary exactly where the host's BPE happened to
+    /// split it.
+    pub token_healing: bool,
 }
 
 impl LlgConstraintInit {
@@ -263,6 +269,44 @@ impl LlgConstraintInit {
         let parser = self.build_parser(grammar, vec![])?;
         Ok(Constraint::new(parser))
     }
+
+    /// Like `build_constraint`, but if `token_healing` is set, also computes
+    /// the mandatory byte prefix the grammar must start with (derived from
+    /// the tail of `prompt_tokens`) and how many prompt tokens it replaces.
+    /// Returns `(constraint, healed_prefix, tokens_to_backtrack)`.
+    pub fn build_constraint_for_prompt(
+        &self,
+        grammar: TopLevelGrammar,
+        prompt_tokens: &[LlgToken],
+    ) -> Result<(Constraint, Vec<u8>, u32)> {
+        let constraint = self.build_constraint(grammar)?;
+        if !self.token_healing {
+            return Ok((constraint, vec![], 0));
+        }
+        let tok_env = self.tok_env()?;
+        let (prefix, backtrack) = token_heal_prefix(&tok_env, prompt_tokens);
+        Ok((constraint, prefix, backtrack))
+    }
+}
+
+/// Given the trailing tokens of a prompt, pop tokens whose bytes could be a
+/// strict prefix of some longer vocabulary token (i.e. the model's greedy
+/// tokenizer might have merged them differently had generation started
+/// there), and return the popped bytes as a mandatory prefix plus how many
+/// tokens were popped.
+fn token_heal_prefix(tok_env: &TokEnv, prompt_tokens: &[LlgToken]) -> (Vec<u8>, u32) {
+    let trie = tok_env.tok_trie();
+    let mut prefix = Vec::new();
+    let mut backtrack = 0u32;
+    for &tok in prompt_tokens.iter().rev() {
+        let bytes = trie.token(tok);
+        if bytes.is_empty() || !trie.has_extensions(bytes) {
+            break;
+        }
+        prefix.splice(0..0, bytes.iter().cloned());
+        backtrack += 1;
+    }
+    (prefix, backtrack)
 }
 
 #[derive(Clone)]
@@ -278,25 +322,132 @@ pub struct LlgConstraintStep {
 
 unsafe impl Send for LlgConstraintStep {}
 
+/// Coarse category for a grammar-compilation failure, so hosts can branch on
+/// *why* a `json_schema`/`lark`/`llguidance` grammar was rejected without
+/// string-matching the error message.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LlgErrorKind {
+    /// No error.
+    None,
+    InvalidUtf8,
+    JsonParse,
+    SchemaUnsupported,
+    LarkSyntax,
+    LimitExceeded,
+    Runtime,
+}
+
+/// Structured counterpart to `llg_get_error()`: carries an error category
+/// and, for JSON parse errors (from `serde_json`'s `Error::column`/`line`),
+/// a byte offset into the input grammar/schema string where parsing failed,
+/// so tooling can underline the offending span. Other errors, lark syntax
+/// errors included, carry no offset.
+#[repr(C)]
+pub struct LlgError {
+    pub kind: LlgErrorKind,
+    /// Byte offset into the grammar/schema source, or `usize::MAX` if not
+    /// available for this error.
+    pub byte_offset: usize,
+    /// Null-terminated error message, valid until the next `llg_*()` call on
+    /// this constraint or until it is freed.
+    pub message: *const c_char,
+}
+
+#[derive(Clone)]
+struct StructuredError {
+    kind: LlgErrorKind,
+    byte_offset: usize,
+}
+
+impl StructuredError {
+    fn none() -> Self {
+        StructuredError {
+            kind: LlgErrorKind::None,
+            byte_offset: usize::MAX,
+        }
+    }
+
+    /// Best-effort classification of an `anyhow::Error` coming out of
+    /// grammar construction. This is deliberately conservative: unrecognized
+    /// failures fall back to `Runtime` rather than guessing.
+    fn classify(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<serde_json::Error>() {
+            return StructuredError {
+                kind: LlgErrorKind::JsonParse,
+                byte_offset: json_error_byte_offset(e),
+            };
+        }
+        let msg = e.to_string();
+        let kind = if msg.contains("Invalid UTF-8") {
+            LlgErrorKind::InvalidUtf8
+        } else if msg.to_lowercase().contains("lark") {
+            LlgErrorKind::LarkSyntax
+        } else if msg.to_lowercase().contains("limit") {
+            LlgErrorKind::LimitExceeded
+        } else if msg.to_lowercase().contains("unsupported") {
+            LlgErrorKind::SchemaUnsupported
+        } else {
+            LlgErrorKind::Runtime
+        };
+        StructuredError {
+            kind,
+            byte_offset: usize::MAX,
+        }
+    }
+}
+
+/// `serde_json::Error` only exposes line/column, not a byte offset; since we
+/// don't have the original source string at this point, we report the
+/// column on the error's line as a best-effort proxy (exact for single-line
+/// schemas, which is the common case).
+fn json_error_byte_offset(e: &serde_json::Error) -> usize {
+    if e.line() <= 1 {
+        e.column().saturating_sub(1)
+    } else {
+        usize::MAX
+    }
+}
+
 pub struct LlgConstraint {
     local_error: Option<String>,
+    structured_error: StructuredError,
     last_logs: String,
     pub(crate) constraint: Option<Constraint>,
     last_commit_result: CommitResult,
+    /// Mandatory prefix bytes from token healing, not yet fully consumed.
+    healed_prefix: Vec<u8>,
+    /// Number of prompt tokens the healed prefix should backtrack over; only
+    /// meaningful until the first `llg_commit_token()`.
+    healed_backtrack: u32,
+    /// Number of tokens committed so far, indexed by checkpoint id (see
+    /// `llg_constraint_push_checkpoint`/`llg_constraint_rollback`). Rolling
+    /// back to a checkpoint just replays `TokenParser::rollback` for the
+    /// difference, so it stays O(k) in the number of tokens undone rather
+    /// than deep-cloning the grammar per branch.
+    checkpoints: Vec<u32>,
+    tokens_committed: u32,
 }
 
 pub struct LlgStopController {
     stop_controller: StopController,
     last_result: String,
+    snapshots: std::collections::HashMap<u64, (StopController, String)>,
+    next_snapshot: u64,
 }
 
 impl Clone for LlgConstraint {
     fn clone(&self) -> Self {
         LlgConstraint {
             local_error: self.local_error.clone(),
+            structured_error: self.structured_error.clone(),
             last_logs: self.last_logs.clone(),
             constraint: self.constraint.clone(),
             last_commit_result: self.last_commit_result.clone(),
+            healed_prefix: self.healed_prefix.clone(),
+            healed_backtrack: self.healed_backtrack,
+            checkpoints: self.checkpoints.clone(),
+            tokens_committed: self.tokens_committed,
         }
     }
 }
@@ -305,9 +456,14 @@ impl Default for LlgConstraint {
     fn default() -> Self {
         LlgConstraint {
             local_error: None,
+            structured_error: StructuredError::none(),
             last_logs: "\x00".to_string(),
             constraint: None,
             last_commit_result: CommitResult::default(),
+            healed_prefix: vec![],
+            healed_backtrack: 0,
+            checkpoints: vec![],
+            tokens_committed: 0,
         }
     }
 }
@@ -371,7 +527,8 @@ fn new_constraint_lark(init: &LlgConstraintInit, lark: *const c_char) -> Result<
 fn new_constraint_json(init: &LlgConstraintInit, json_schema: *const c_char) -> Result<Constraint> {
     let json_schema = unsafe { c_str_to_str(json_schema, "json_schema") }?;
     let json_schema = serde_json::from_str(json_schema)
-        .map_err(|e| anyhow::anyhow!("Invalid JSON in json_schema: {e}"))?;
+        .map_err(anyhow::Error::new)
+        .context("Invalid JSON in json_schema")?;
     let grammar = TopLevelGrammar::from_json_schema(json_schema);
     init.build_constraint(grammar)
 }
@@ -379,7 +536,8 @@ fn new_constraint_json(init: &LlgConstraintInit, json_schema: *const c_char) ->
 fn new_constraint(init: &LlgConstraintInit, grammar_json: *const c_char) -> Result<Constraint> {
     let grammar_json = unsafe { c_str_to_str(grammar_json, "grammar_json") }?;
     let grammar: TopLevelGrammar = serde_json::from_str(grammar_json)
-        .map_err(|e| anyhow::anyhow!("Invalid JSON in grammar_json: {e}"))?;
+        .map_err(anyhow::Error::new)
+        .context("Invalid JSON in grammar_json")?;
     init.build_constraint(grammar)
 }
 
@@ -416,8 +574,23 @@ impl LlgConstraint {
 
     pub(crate) fn set_error(&mut self, e: &str) {
         self.constraint = None;
+        self.structured_error = StructuredError {
+            kind: LlgErrorKind::Runtime,
+            byte_offset: usize::MAX,
+        };
         self.local_error = Some(format!("{e}\0"));
     }
+
+    /// Like `set_error`, but also records an `LlgErrorKind` and (when
+    /// available) a byte offset, for `llg_get_error_info`.
+    pub(crate) fn set_error_from(&mut self, e: &anyhow::Error) {
+        self.constraint = None;
+        self.structured_error = StructuredError::classify(e);
+        // `{:#}` renders the full `.context()` chain (e.g. "Invalid JSON in
+        // json_schema: expected value at line 1 column 5"); plain `{}` would
+        // only show the outermost context message.
+        self.local_error = Some(format!("{e:#}\0"));
+    }
 }
 
 /// Set the default values for the ConstraintInit
@@ -436,6 +609,7 @@ pub extern "C" fn llg_constraint_init_set_defaults(
         ff_tokens_ok: false,
         backtrack_ok: false,
         limits: ParserLimits::default(),
+        token_healing: false,
     };
 }
 
@@ -444,7 +618,7 @@ pub fn constraint_to_llg(c: Result<Constraint>) -> *mut LlgConstraint {
 
     match c {
         Ok(constraint) => res.constraint = Some(constraint),
-        Err(e) => res.set_error(&e.to_string()),
+        Err(e) => res.set_error_from(&e),
     };
 
     Box::into_raw(Box::new(res))
@@ -502,6 +676,67 @@ pub extern "C" fn llg_new_constraint_any(
     constraint_to_llg(new_constraint_any(init, constraint_type, data))
 }
 
+/// Like `llg_new_constraint_any`, but also applies token healing (if
+/// `init.token_healing` is set): `prompt_tokens` is the tail of the prompt as
+/// fed to the model, and trailing tokens that could be a strict prefix of a
+/// longer vocabulary token are popped and turned into a mandatory prefix for
+/// the grammar. Use `llg_constraint_healed_backtrack` to find out how many
+/// prompt tokens were healed away so the engine can backtrack them.
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_new_constraint_any_for_prompt(
+    init: &LlgConstraintInit,
+    constraint_type: *const c_char,
+    data: *const c_char,
+    prompt_tokens: *const u32,
+    prompt_tokens_len: usize,
+) -> *mut LlgConstraint {
+    let grammar = (|| -> Result<TopLevelGrammar> {
+        let tp = unsafe { c_str_to_str(constraint_type, "constraint_type") }?;
+        Ok(match tp {
+            "regex" => TopLevelGrammar::from_regex(unsafe { c_str_to_str(data, "regex") }?),
+            "json" | "json_schema" => {
+                let json_schema = unsafe { c_str_to_str(data, "json_schema") }?;
+                let json_schema = serde_json::from_str(json_schema)
+                    .map_err(anyhow::Error::new)
+                    .context("Invalid JSON in json_schema")?;
+                TopLevelGrammar::from_json_schema(json_schema)
+            }
+            "lark" => TopLevelGrammar::from_lark(unsafe { c_str_to_str(data, "lark") }?.to_string()),
+            "llguidance" | "guidance" => {
+                let grammar_json = unsafe { c_str_to_str(data, "grammar_json") }?;
+                serde_json::from_str(grammar_json)
+                    .map_err(anyhow::Error::new)
+                    .context("Invalid JSON in grammar_json")?
+            }
+            _ => bail!("unknown constraint type: {tp}"),
+        })
+    })();
+
+    let prompt_tokens = unsafe { std::slice::from_raw_parts(prompt_tokens, prompt_tokens_len) };
+    let built = grammar.and_then(|g| init.build_constraint_for_prompt(g, prompt_tokens));
+
+    let mut res = LlgConstraint::default();
+    match built {
+        Ok((constraint, healed_prefix, healed_backtrack)) => {
+            res.constraint = Some(constraint);
+            res.healed_prefix = healed_prefix;
+            res.healed_backtrack = healed_backtrack;
+        }
+        Err(e) => res.set_error_from(&e),
+    }
+    Box::into_raw(Box::new(res))
+}
+
+/// Number of prompt tokens that token healing popped and that the engine
+/// should backtrack before appending anything sampled under this
+/// constraint. Always 0 unless `init.token_healing` was set.
+#[no_mangle]
+pub extern "C" fn llg_constraint_healed_backtrack(cc: &LlgConstraint) -> u32 {
+    cc.healed_backtrack
+}
+
 /// Get the error message from the constraint or null if there is no error.
 /// After it returns a non-null value, it will always return it until the constraint is freed
 /// using llg_free_constraint() (at which point the pointer will be invalid).
@@ -510,6 +745,28 @@ pub extern "C" fn llg_get_error(cc: &LlgConstraint) -> *const c_char {
     cc.get_error()
 }
 
+/// Populate `*info` with the structured error (category + byte offset into
+/// the grammar/schema source, when known) for the current error, or with
+/// `LlgErrorKind::None` if there is no error. Returns 0 on success, -1 if
+/// there is no error to report.
+#[no_mangle]
+pub extern "C" fn llg_get_error_info(cc: &LlgConstraint, info: &mut LlgError) -> i32 {
+    if cc.local_error.is_none() {
+        *info = LlgError {
+            kind: LlgErrorKind::None,
+            byte_offset: usize::MAX,
+            message: std::ptr::null(),
+        };
+        return -1;
+    }
+    *info = LlgError {
+        kind: cc.structured_error.kind,
+        byte_offset: cc.structured_error.byte_offset,
+        message: cc.get_error(),
+    };
+    0
+}
+
 /// Get the current temperature of the constraint.
 /// It is updated by mask computation.
 #[no_mangle]
@@ -533,7 +790,13 @@ pub extern "C" fn llg_is_stopped(cc: &LlgConstraint) -> bool {
 pub extern "C" fn llg_compute_mask(cc: &mut LlgConstraint, res_p: &mut LlgMaskResult) -> i32 {
     if let Some(constraint) = &mut cc.constraint {
         match constraint.compute_mask() {
-            Ok(r) => {
+            Ok(mut r) => {
+                if !cc.healed_prefix.is_empty() {
+                    if let Some(mask) = r.sample_mask.as_mut() {
+                        let trie = constraint.parser.token_env.tok_trie();
+                        restrict_to_prefix(mask, trie, &cc.healed_prefix);
+                    }
+                }
                 let r = LlgMaskResult {
                     sample_mask: r
                         .sample_mask
@@ -550,6 +813,21 @@ pub extern "C" fn llg_compute_mask(cc: &mut LlgConstraint, res_p: &mut LlgMaskRe
     cc.get_error_code()
 }
 
+/// Restrict `mask` in place to tokens whose byte string starts with
+/// `prefix` (or is itself a strict prefix of `prefix`, for short healed
+/// continuations), clearing every other bit.
+fn restrict_to_prefix(mask: &mut toktrie::SimpleVob, trie: &TokTrie, prefix: &[u8]) {
+    for tok in 0..trie.vocab_size() as u32 {
+        if !mask.is_allowed(tok) {
+            continue;
+        }
+        let bytes = trie.token(tok);
+        if !(bytes.starts_with(prefix) || prefix.starts_with(bytes)) {
+            mask.disallow_token(tok);
+        }
+    }
+}
+
 /// Commit the token sampled with the mask returned from llg_compute_mask().
 /// Can be run on the critical path of sampling (is fast).
 /// Returns 0 on success and -1 on error (use llg_get_error() to get the exact error).
@@ -567,8 +845,33 @@ pub extern "C" fn llg_commit_token(
         } else {
             None
         };
+        let committed_bytes = token.map(|t| trie.token(t).to_vec());
         match constraint.commit_token(token) {
             Ok(r) => {
+                // `healed_backtrack` only matters for the very first mask
+                // (the host reads it once, before the first commit), so it
+                // can always be dropped here. `healed_prefix` is different:
+                // a short healed continuation can be only a strict prefix
+                // of it, in which case the remaining mandatory bytes must
+                // still be enforced on the *next* mask. Only clear it once
+                // the committed token's bytes fully cover what was left.
+                cc.healed_backtrack = 0;
+                if !cc.healed_prefix.is_empty() {
+                    match &committed_bytes {
+                        Some(bytes) if bytes.starts_with(&cc.healed_prefix) => {
+                            cc.healed_prefix.clear();
+                        }
+                        Some(bytes) if cc.healed_prefix.starts_with(bytes) => {
+                            cc.healed_prefix.drain(..bytes.len());
+                        }
+                        // Committed bytes diverged from the healed prefix
+                        // entirely (shouldn't happen given `compute_mask`
+                        // restricts to it, but don't leave stale state
+                        // around if some other path skipped that check).
+                        _ => cc.healed_prefix.clear(),
+                    }
+                }
+                cc.tokens_committed += 1;
                 // store it, so it survives until the next call to llg_*()
                 cc.last_commit_result = r;
                 let res = LlgCommitResult::from_commit_result(&cc.last_commit_result);
@@ -607,12 +910,133 @@ pub unsafe extern "C" fn llg_par_compute_mask(
     }
 }
 
+/// Opaque handle to a pending job submitted to an `LlgMaskPool`.
+pub type LlgMaskPoolJob = u64;
+
+/// A persistent worker pool for non-blocking mask computation: submit a
+/// `LlgConstraintStep` and get an opaque job handle back immediately, with
+/// `done_cb` fired on a worker thread once the mask has been written to
+/// `step.mask_dest`. This lets a serving loop keep many mask computations in
+/// flight without managing its own batching boundaries, unlike
+/// `llg_par_compute_mask` which blocks until a whole batch finishes.
+pub struct LlgMaskPool {
+    #[cfg(feature = "rayon")]
+    pool: rayon::ThreadPool,
+    next_job: std::sync::atomic::AtomicU64,
+}
+
+/// Create a new mask-computation worker pool with `n_threads` workers.
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_mask_pool_new(n_threads: usize) -> *mut LlgMaskPool {
+    #[cfg(feature = "rayon")]
+    {
+        match rayon::ThreadPoolBuilder::new().num_threads(n_threads).build() {
+            Ok(pool) => Box::into_raw(Box::new(LlgMaskPool {
+                pool,
+                next_job: std::sync::atomic::AtomicU64::new(1),
+            })),
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        let _ = n_threads;
+        std::ptr::null_mut()
+    }
+}
+
+/// Submit a single mask-computation job to the pool. Returns immediately
+/// with an opaque, non-zero job handle; `done_cb(user_data)` is invoked on a
+/// worker thread once `step.mask_dest` has been filled in (the mask remains
+/// valid per the same rules as `llg_compute_mask`).
+/// # Safety
+/// This function should only be called from C code. `step.constraint` and
+/// `step.mask_dest` must stay valid until `done_cb` fires.
+#[no_mangle]
+pub unsafe extern "C" fn llg_mask_pool_submit(
+    pool: &LlgMaskPool,
+    step: LlgConstraintStep,
+    user_data: *const c_void,
+    done_cb: LlgCallback,
+) -> LlgMaskPoolJob {
+    let job = pool
+        .next_job
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    #[cfg(feature = "rayon")]
+    {
+        struct SendStep(LlgConstraintStep, *const c_void, LlgCallback);
+        unsafe impl Send for SendStep {}
+        let payload = SendStep(step, user_data, done_cb);
+        pool.pool.spawn(move || {
+            let SendStep(step, user_data, done_cb) = payload;
+            crate::ffi_par::par_compute_mask(vec![step], user_data, done_cb);
+        });
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        let _ = (step, user_data, done_cb);
+        panic!("llg_mask_pool_submit: rayon feature is not enabled");
+    }
+
+    job
+}
+
+/// Free the mask pool. Does not wait for in-flight jobs; callers should make
+/// sure all submitted `done_cb`s have fired first.
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_mask_pool_free(pool: *mut LlgMaskPool) {
+    unsafe {
+        drop(Box::from_raw(pool));
+    }
+}
+
 /// Clone the constraint
 #[no_mangle]
 pub extern "C" fn llg_clone_constraint(cc: &LlgConstraint) -> *mut LlgConstraint {
     Box::into_raw(Box::new(cc.clone()))
 }
 
+/// Push a checkpoint recording the constraint's current parser position and
+/// return its id. Cheaper than `llg_clone_constraint` for speculative
+/// decoding/beam search: it snapshots only the number of tokens committed so
+/// far rather than deep-cloning the grammar, so accepting a branch (just
+/// dropping the checkpoint) is O(1).
+#[no_mangle]
+pub extern "C" fn llg_constraint_push_checkpoint(cc: &mut LlgConstraint) -> u32 {
+    let id = cc.checkpoints.len() as u32;
+    cc.checkpoints.push(cc.tokens_committed);
+    id
+}
+
+/// Roll back to `checkpoint_id`, discarding any tokens committed via
+/// `llg_commit_token` since then. Checkpoints pushed after `checkpoint_id`
+/// are also discarded. O(k) in the number of tokens rolled back. Returns 0
+/// on success and -1 on error (e.g. unknown checkpoint id).
+#[no_mangle]
+pub extern "C" fn llg_constraint_rollback(cc: &mut LlgConstraint, checkpoint_id: u32) -> i32 {
+    let Some(&target) = cc.checkpoints.get(checkpoint_id as usize) else {
+        cc.set_error("unknown checkpoint id");
+        return -1;
+    };
+    if let Some(constraint) = &mut cc.constraint {
+        let to_rollback = (cc.tokens_committed - target) as usize;
+        if let Err(e) = constraint.parser.rollback(to_rollback) {
+            cc.set_error(&e.to_string());
+            return -1;
+        }
+    }
+    cc.tokens_committed = target;
+    cc.checkpoints.truncate(checkpoint_id as usize + 1);
+    cc.healed_prefix.clear();
+    cc.healed_backtrack = 0;
+    0
+}
+
 /// Construct a new tokenizer from the given TokenizerInit
 #[no_mangle]
 pub extern "C" fn llg_new_tokenizer(
@@ -713,6 +1137,34 @@ pub unsafe extern "C" fn llg_stringify_tokens(
     s.len() + 1
 }
 
+/// Serialize the constraint's current Earley parse as a Graphviz `digraph`
+/// (`DotKind::RunState`): one node per dotted item in the current rows,
+/// labeled with its origin position, with edges for scans/completions; live
+/// items are filled green and completed ones grey. Lexer states and the
+/// byte ranges currently allowed are not shown. Lets a host inspect the
+/// parse behind a given token mask at a decoding step. Follows the same
+/// null-terminated, size-returning convention as `llg_stringify_tokens`.
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_stringify_parser_dot(
+    cc: &LlgConstraint,
+    output: *mut c_char,
+    output_len: usize,
+) -> usize {
+    let s = match &cc.constraint {
+        Some(constraint) => constraint.parser.to_dot(crate::dot::DotKind::RunState),
+        None => String::new(),
+    };
+    let s = s.as_bytes();
+    let len = std::cmp::min(s.len(), output_len.saturating_sub(1));
+    unsafe {
+        std::ptr::copy_nonoverlapping(s.as_ptr(), output as *mut u8, len);
+        *output.add(len) = 0;
+    }
+    s.len() + 1
+}
+
 /// Free the tokenizer. Should *NOT* be called while there are still constraints using it.
 /// # Safety
 /// This function should only be called from C code.
@@ -737,11 +1189,27 @@ pub unsafe extern "C" fn llg_free_constraint(cc: *mut LlgConstraint) {
 /// The logs are null-terminated.
 /// The logs are kept in the constraint until the next call to this function
 /// or until the constraint is freed.
+///
+/// This is the legacy polling path; when a callback is registered with
+/// `llg_set_log_callback`, records are delivered there instead and this
+/// function returns an empty string.
 #[no_mangle]
 pub extern "C" fn llg_flush_logs(cc: &mut LlgConstraint) -> *const c_char {
     if let Some(constraint) = &mut cc.constraint {
         let s = constraint.flush_logs();
-        if s.contains('\0') {
+        if log_sink::has_callback() {
+            // A structured sink is registered: deliver each buffered line as
+            // its own record (instead of flattening everything into the
+            // legacy buffer below, which is left empty now that delivery is
+            // actually happening).
+            for line in s.lines() {
+                if line.is_empty() {
+                    continue;
+                }
+                log_sink::emit(log_sink::classify_level(line), line.to_string());
+            }
+            cc.last_logs.clear();
+        } else if s.contains('\0') {
             cc.last_logs = s.replace('\0', "\\0");
         } else {
             cc.last_logs = s;
@@ -751,6 +1219,131 @@ pub extern "C" fn llg_flush_logs(cc: &mut LlgConstraint) -> *const c_char {
     cc.last_logs.as_ptr() as *const c_char
 }
 
+/// Severity of a structured log record delivered to an `LlgLogCallback`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LlgLogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Callback invoked once per log record, from a dedicated worker thread (not
+/// the thread that produced the record). `msg` is valid only for the
+/// duration of the call; embedded NUL bytes in the message have already been
+/// escaped as `\0`.
+pub type LlgLogCallback =
+    Option<extern "C" fn(level: LlgLogLevel, msg: *const c_char, msg_len: usize, user_data: *mut c_void)>;
+
+mod log_sink {
+    use super::{c_void, LlgLogLevel};
+    use std::sync::{mpsc, Mutex, OnceLock};
+
+    // Wraps the raw C callback + user_data pair so it can be shared with the
+    // drain thread; the host is responsible for its thread-safety, same as
+    // every other `user_data` pointer in this FFI.
+    struct RawCallback(
+        extern "C" fn(LlgLogLevel, *const super::c_char, usize, *mut c_void),
+        *mut c_void,
+    );
+    unsafe impl Send for RawCallback {}
+
+    struct Record {
+        level: LlgLogLevel,
+        msg: String,
+    }
+
+    struct Sink {
+        sender: mpsc::SyncSender<Record>,
+    }
+
+    static SINK: OnceLock<Mutex<Option<Sink>>> = OnceLock::new();
+
+    fn sink_slot() -> &'static Mutex<Option<Sink>> {
+        SINK.get_or_init(|| Mutex::new(None))
+    }
+
+    pub fn has_callback() -> bool {
+        sink_slot().lock().unwrap().is_some()
+    }
+
+    /// Register (or, with `cb = None`, unregister) the process-wide log
+    /// callback. Bounded at 4096 in-flight records so a slow/stuck host
+    /// callback applies backpressure rather than growing unbounded; records
+    /// beyond that are dropped (not blocked on), since logging must never
+    /// stall the decode hot path.
+    pub fn set_callback(cb: super::LlgLogCallback, user_data: *mut c_void) {
+        // Unregistering (or replacing) drops the old sender, which makes the
+        // old drain thread's `recv()` return `Err` once the queue empties,
+        // i.e. any in-flight records are delivered before it exits.
+        *sink_slot().lock().unwrap() = None;
+
+        let Some(cb) = cb else { return };
+        let raw = RawCallback(cb, user_data);
+
+        let (sender, receiver) = mpsc::sync_channel::<Record>(4096);
+        std::thread::spawn(move || {
+            let RawCallback(cb, user_data) = raw;
+            while let Ok(rec) = receiver.recv() {
+                let escaped;
+                let msg: &str = if rec.msg.contains('\0') {
+                    escaped = rec.msg.replace('\0', "\\0");
+                    &escaped
+                } else {
+                    &rec.msg
+                };
+                cb(
+                    rec.level,
+                    msg.as_ptr() as *const super::c_char,
+                    msg.len(),
+                    user_data,
+                );
+            }
+        });
+        *sink_slot().lock().unwrap() = Some(Sink { sender });
+    }
+
+    /// Push a record to the registered callback's worker thread. No-op if no
+    /// callback is registered (callers should fall back to the legacy
+    /// buffer in that case).
+    pub fn emit(level: LlgLogLevel, msg: String) {
+        if let Some(sink) = sink_slot().lock().unwrap().as_ref() {
+            let _ = sink.sender.try_send(Record { level, msg });
+        }
+    }
+
+    /// Guess the severity of a buffered log line from its leading marker.
+    /// The internal logger doesn't tag lines with a machine-readable level,
+    /// so this is best-effort; anything unrecognized is reported as `Info`.
+    pub fn classify_level(line: &str) -> LlgLogLevel {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("Error") || trimmed.starts_with("error") {
+            LlgLogLevel::Error
+        } else if trimmed.starts_with("Warning") || trimmed.starts_with("warn") {
+            LlgLogLevel::Warn
+        } else if trimmed.starts_with("Debug") || trimmed.starts_with("debug") {
+            LlgLogLevel::Debug
+        } else {
+            LlgLogLevel::Info
+        }
+    }
+}
+
+/// Register a process-wide structured log callback: each log record is
+/// delivered as it is emitted (severity + message), via a bounded channel
+/// drained on a dedicated worker thread, instead of being flattened into the
+/// `llg_flush_logs()` buffer. Pass `cb = None` to unregister (and drain any
+/// in-flight records) and fall back to the legacy buffer.
+/// # Safety
+/// This function should only be called from C code. `user_data` must remain
+/// valid (and safe to use from the drain thread) until unregistered.
+#[no_mangle]
+pub unsafe extern "C" fn llg_set_log_callback(cb: LlgLogCallback, user_data: *mut c_void) {
+    log_sink::set_callback(cb, user_data);
+}
+
 fn build_stop_controller(
     tokenizer: &LlgTokenizer,
     stop_tokens: &[u32],
@@ -761,12 +1354,133 @@ fn build_stop_controller(
     } else {
         Some(unsafe { c_str_to_str(stop_rx, "stop_rx") }?.to_string())
     };
-    StopController::new(
+    if let Some(cached) = stop_controller_cache::get(tokenizer, stop_tokens, stop_rx.as_deref()) {
+        return Ok(cached);
+    }
+    let built = StopController::new(
         tokenizer.token_env.clone(),
         stop_tokens.to_vec(),
-        stop_rx,
+        stop_rx.clone(),
         vec![],
-    )
+    )?;
+    stop_controller_cache::insert(tokenizer, stop_tokens, stop_rx.as_deref(), &built);
+    Ok(built)
+}
+
+mod stop_controller_cache {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Mutex, OnceLock};
+
+    use super::{LlgTokenizer, StopController};
+    use crate::compiled_cache::tokenizer_fingerprint;
+
+    const DEFAULT_CAPACITY: usize = 256;
+
+    struct Cache {
+        capacity: usize,
+        entries: HashMap<u64, StopController>,
+        // front = least recently used
+        order: VecDeque<u64>,
+    }
+
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+    fn cache() -> &'static Mutex<Cache> {
+        CACHE.get_or_init(|| {
+            Mutex::new(Cache {
+                capacity: DEFAULT_CAPACITY,
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            })
+        })
+    }
+
+    /// 64-bit fingerprint over (tokenizer identity, sorted stop_tokens,
+    /// stop_rx), used as the cache key. The tokenizer identity must be part
+    /// of the key because token<->byte mappings differ between tokenizers
+    /// and would otherwise alias.
+    fn fingerprint(tokenizer: &LlgTokenizer, stop_tokens: &[u32], stop_rx: Option<&str>) -> u64 {
+        let mut sorted = stop_tokens.to_vec();
+        sorted.sort_unstable();
+        let mut buf = Vec::with_capacity(sorted.len() * 4 + 16);
+        buf.extend_from_slice(&tokenizer_fingerprint(&tokenizer.token_env).to_le_bytes());
+        for t in &sorted {
+            buf.extend_from_slice(&t.to_le_bytes());
+        }
+        if let Some(rx) = stop_rx {
+            buf.extend_from_slice(rx.as_bytes());
+        }
+        xxhash_rust::xxh3::xxh3_64(&buf)
+    }
+
+    pub(super) fn get(
+        tokenizer: &LlgTokenizer,
+        stop_tokens: &[u32],
+        stop_rx: Option<&str>,
+    ) -> Option<StopController> {
+        let key = fingerprint(tokenizer, stop_tokens, stop_rx);
+        let mut cache = cache().lock().unwrap();
+        let hit = cache.entries.get(&key).cloned();
+        if hit.is_some() {
+            cache.order.retain(|k| *k != key);
+            cache.order.push_back(key);
+        }
+        hit
+    }
+
+    pub(super) fn insert(
+        tokenizer: &LlgTokenizer,
+        stop_tokens: &[u32],
+        stop_rx: Option<&str>,
+        built: &StopController,
+    ) {
+        let key = fingerprint(tokenizer, stop_tokens, stop_rx);
+        let mut cache = cache().lock().unwrap();
+        if !cache.entries.contains_key(&key) {
+            while cache.entries.len() >= cache.capacity {
+                if let Some(lru) = cache.order.pop_front() {
+                    cache.entries.remove(&lru);
+                } else {
+                    break;
+                }
+            }
+            cache.order.push_back(key);
+        }
+        cache.entries.insert(key, built.clone());
+    }
+
+    pub(super) fn set_capacity(n: usize) {
+        let mut cache = cache().lock().unwrap();
+        cache.capacity = n.max(1);
+        while cache.entries.len() > cache.capacity {
+            if let Some(lru) = cache.order.pop_front() {
+                cache.entries.remove(&lru);
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub(super) fn clear() {
+        let mut cache = cache().lock().unwrap();
+        cache.entries.clear();
+        cache.order.clear();
+    }
+}
+
+/// Set the maximum number of compiled stop-controller automatons kept in the
+/// process-wide cache (default 256). Lowering this immediately evicts the
+/// least-recently-used entries, for hosts that need deterministic memory
+/// behavior.
+#[no_mangle]
+pub extern "C" fn llg_stop_controller_cache_capacity(n: usize) {
+    stop_controller_cache::set_capacity(n);
+}
+
+/// Drop all cached compiled stop controllers.
+#[no_mangle]
+pub extern "C" fn llg_clear_stop_controller_cache() {
+    stop_controller_cache::clear();
 }
 
 fn save_error_string(e: impl Display, error_string: *mut c_char, error_string_len: usize) {
@@ -798,6 +1512,8 @@ pub unsafe extern "C" fn llg_new_stop_controller(
         Ok(stop_controller) => Box::into_raw(Box::new(LlgStopController {
             stop_controller,
             last_result: String::new(),
+            snapshots: std::collections::HashMap::new(),
+            next_snapshot: 1,
         })),
         Err(e) => {
             save_error_string(e, error_string, error_string_len);
@@ -824,6 +1540,74 @@ pub extern "C" fn llg_stop_commit_token(
     stop_ctrl.last_result.as_ptr() as *const c_char
 }
 
+/// Commit a slice of tokens to the stop-sequence controller in one call,
+/// for speculative decoding/beam search where a block of draft tokens is
+/// proposed at once. Stops early if the controller enters its stopped state
+/// partway through. Returns the number of tokens actually consumed before
+/// stopping (or `len` if it never stopped); the concatenation of their
+/// outputs is returned in `*output_p` the same way as
+/// `llg_stop_commit_token`'s single result (i.e. the accumulated text, not
+/// one string per token).
+/// # Safety
+/// This function should only be called from C code.
+#[no_mangle]
+pub unsafe extern "C" fn llg_stop_commit_tokens(
+    stop_ctrl: &mut LlgStopController,
+    tokens: *const u32,
+    len: usize,
+    output_p: &mut *const c_char,
+    output_len_p: &mut usize,
+    is_stopped_p: &mut bool,
+) -> usize {
+    let tokens = unsafe { std::slice::from_raw_parts(tokens, len) };
+    let mut combined = String::new();
+    let mut accepted = 0;
+    for &token in tokens {
+        let r = stop_ctrl.stop_controller.commit_token(token);
+        combined.push_str(&r);
+        accepted += 1;
+        if stop_ctrl.stop_controller.is_stopped() {
+            break;
+        }
+    }
+    *output_len_p = combined.len();
+    *is_stopped_p = stop_ctrl.stop_controller.is_stopped();
+    stop_ctrl.last_result = format!("{combined}\0");
+    *output_p = stop_ctrl.last_result.as_ptr() as *const c_char;
+    accepted
+}
+
+/// Snapshot the stop controller's current position and pending-output
+/// state, returning an opaque handle. `llg_stop_rollback` restores exactly
+/// this state, discarding anything committed since, so rejected speculative
+/// tokens can be cheaply discarded.
+#[no_mangle]
+pub extern "C" fn llg_stop_snapshot(stop_ctrl: &mut LlgStopController) -> u64 {
+    let handle = stop_ctrl.next_snapshot;
+    stop_ctrl.next_snapshot += 1;
+    stop_ctrl.snapshots.insert(
+        handle,
+        (stop_ctrl.stop_controller.clone(), stop_ctrl.last_result.clone()),
+    );
+    handle
+}
+
+/// Restore the stop controller to the state captured by `llg_stop_snapshot`.
+/// Fully restores `is_stopped` and any buffered partial-match text, so
+/// subsequent commits behave identically to never having applied the
+/// rolled-back tokens. Returns 0 on success, -1 if `handle` is unknown.
+#[no_mangle]
+pub extern "C" fn llg_stop_rollback(stop_ctrl: &mut LlgStopController, handle: u64) -> i32 {
+    match stop_ctrl.snapshots.get(&handle) {
+        Some((ctrl, last_result)) => {
+            stop_ctrl.stop_controller = ctrl.clone();
+            stop_ctrl.last_result = last_result.clone();
+            0
+        }
+        None => -1,
+    }
+}
+
 /// Free the stop-sequence controller
 /// # Safety
 /// This function should only be called from C code.
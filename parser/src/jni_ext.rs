@@ -0,0 +1,131 @@
+//! Idiomatic JNI bindings for the stop-controller lifecycle, gated behind
+//! the `jni` feature. Mirrors `llg_new_stop_controller`/`llg_stop_commit_token`/
+//! `llg_free_stop_controller` in `ffi.rs`, but returns Java-native types
+//! instead of raw pointers-into-buffers, and rethrows errors as exceptions
+//! instead of stashing them in an error-string out-param. The Rust core
+//! (`StopController`) is untouched; this is purely a thin wrapper so the C
+//! ABI and JNI surfaces share one implementation.
+#![cfg(feature = "jni")]
+
+use jni::objects::{JClass, JObject, JString, JValue};
+use jni::sys::{jboolean, jlong, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+
+use crate::StopController;
+
+fn throw(env: &mut JNIEnv, e: impl std::fmt::Display) {
+    let _ = env.throw_new("java/lang/RuntimeException", e.to_string());
+}
+
+fn native_ptr(ctrl: StopController) -> jlong {
+    Box::into_raw(Box::new(ctrl)) as jlong
+}
+
+unsafe fn native_ref<'a>(ptr: jlong) -> &'a mut StopController {
+    unsafe { &mut *(ptr as *mut StopController) }
+}
+
+/// `StopController.nativeNew(tokenizerHandle, stopTokens, stopRx)` ->
+/// native handle, or 0 (with a pending `RuntimeException`) on failure.
+#[no_mangle]
+pub extern "system" fn Java_com_microsoft_llguidance_StopController_nativeNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    tokenizer_handle: jlong,
+    stop_tokens: jni::objects::JIntArray,
+    stop_rx: JString,
+) -> jlong {
+    let tokenizer = unsafe { &*(tokenizer_handle as *const crate::ffi::LlgTokenizer) };
+
+    let len = match env.get_array_length(&stop_tokens) {
+        Ok(n) => n as usize,
+        Err(e) => {
+            throw(&mut env, e);
+            return 0;
+        }
+    };
+    let mut buf = vec![0i32; len];
+    if let Err(e) = env.get_int_array_region(&stop_tokens, 0, &mut buf) {
+        throw(&mut env, e);
+        return 0;
+    }
+    let stop_tokens: Vec<u32> = buf.into_iter().map(|t| t as u32).collect();
+
+    let stop_rx: Option<String> = if stop_rx.is_null() {
+        None
+    } else {
+        match env.get_string(&stop_rx) {
+            Ok(s) => Some(s.into()),
+            Err(e) => {
+                throw(&mut env, e);
+                return 0;
+            }
+        }
+    };
+
+    match StopController::new(tokenizer.token_env.clone(), stop_tokens, stop_rx, vec![]) {
+        Ok(ctrl) => native_ptr(ctrl),
+        Err(e) => {
+            throw(&mut env, e);
+            0
+        }
+    }
+}
+
+/// `StopController.nativeCommitToken(handle, token) -> CommitResult` where
+/// `CommitResult` is `{ String text; boolean isStopped; }`.
+/// # Safety
+/// `handle` must be a live pointer returned by `nativeNew`.
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_microsoft_llguidance_StopController_nativeCommitToken<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    token: jni::sys::jint,
+) -> JObject<'local> {
+    let ctrl = unsafe { native_ref(handle) };
+    let text = ctrl.commit_token(token as u32);
+    let is_stopped: jboolean = if ctrl.is_stopped() { JNI_TRUE } else { JNI_FALSE };
+
+    let text = match env.new_string(text) {
+        Ok(s) => s,
+        Err(e) => {
+            throw(&mut env, e);
+            return JObject::null();
+        }
+    };
+
+    let class = match env.find_class("com/microsoft/llguidance/StopController$CommitResult") {
+        Ok(c) => c,
+        Err(e) => {
+            throw(&mut env, e);
+            return JObject::null();
+        }
+    };
+    match env.new_object(
+        class,
+        "(Ljava/lang/String;Z)V",
+        &[JValue::Object(&text), JValue::Bool(is_stopped)],
+    ) {
+        Ok(obj) => obj,
+        Err(e) => {
+            throw(&mut env, e);
+            JObject::null()
+        }
+    }
+}
+
+/// `StopController.nativeFree(handle)`.
+/// # Safety
+/// `handle` must be a live pointer returned by `nativeNew`, and must not be
+/// used again afterwards.
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_microsoft_llguidance_StopController_nativeFree(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    unsafe {
+        drop(Box::from_raw(handle as *mut StopController));
+    }
+}
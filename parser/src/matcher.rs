@@ -1,11 +1,56 @@
 use anyhow::{anyhow, ensure, Result};
 use toktrie::{SimpleVob, TokEnv, TokenId};
 
-use crate::{api::StopReason, panic_utils, TokenParser};
+use crate::{api::StopReason, dot::DotKind, panic_utils, TokenParser};
+
+/// How `Matcher::validate_with_recovery` should behave once it hits a byte or
+/// token that the grammar rejects.
+#[derive(Clone, Copy, Debug)]
+pub enum RecoveryMode {
+    /// Stop and return what has been collected so far (current behavior,
+    /// minus the panic).
+    Strict,
+    /// Classic panic-mode recovery: skip input forward until we reach
+    /// something some currently-open rule can accept, recording a
+    /// [`ParseError`] for each rejection, bounded by the given budget.
+    Resync {
+        /// Maximum number of bytes to skip while looking for a resync point.
+        max_skipped_bytes: usize,
+        /// Maximum number of [`ParseError`]s to collect before giving up.
+        max_errors: usize,
+    },
+}
+
+/// A single grammar violation recorded while validating pre-existing
+/// text/tokens with [`Matcher::validate_with_recovery`].
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    /// Byte offset (into the concatenation of the validated tokens) at which
+    /// the rejection occurred.
+    pub byte_offset: usize,
+    /// The token that was rejected. Resync skips whole tokens, so every
+    /// error is found at token granularity and this is always `Some`.
+    pub rejected_token: Option<TokenId>,
+    /// Human-readable description of what the parser would have accepted at
+    /// this point (derived from the current Earley row's FOLLOW set).
+    pub expected: Vec<String>,
+}
 
 #[derive(Clone)]
 struct MatcherInner {
     parser: TokenParser,
+    // generation-length guard; `None` means unbounded
+    max_tokens: Option<usize>,
+    tokens_consumed: usize,
+}
+
+impl MatcherInner {
+    /// `None` if there is no `max_tokens` limit, otherwise how many more
+    /// tokens may be consumed before `StopReason::MaxTokensReached` kicks in.
+    fn remaining_tokens(&self) -> Option<usize> {
+        self.max_tokens
+            .map(|max| max.saturating_sub(self.tokens_consumed))
+    }
 }
 
 #[derive(Clone)]
@@ -32,13 +77,36 @@ impl Matcher {
                     if parser.is_fresh() {
                         parser.start_without_prompt();
                     }
-                    Matcher(MatcherState::Normal(MatcherInner { parser }))
+                    Matcher(MatcherState::Normal(MatcherInner {
+                        parser,
+                        max_tokens: None,
+                        tokens_consumed: 0,
+                    }))
                 }
             }
             Err(e) => Matcher(MatcherState::Error(e.to_string())),
         }
     }
 
+    /// Set a hard cap on the number of tokens this matcher will allow to be
+    /// consumed. Once the budget is exhausted, `compute_mask` restricts the
+    /// mask to just the EOS token (if the grammar `is_accepting`) or puts the
+    /// parser in `StopReason::MaxTokensReached`.
+    pub fn set_max_tokens(&mut self, max_tokens: Option<usize>) {
+        if let MatcherState::Normal(inner) = &mut self.0 {
+            inner.max_tokens = max_tokens;
+        }
+    }
+
+    /// How many more tokens can be consumed before `max_tokens` is hit, or
+    /// `None` if there is no limit (or the matcher is in an error state).
+    pub fn remaining_tokens(&self) -> Option<usize> {
+        match &self.0 {
+            MatcherState::Normal(inner) => inner.remaining_tokens(),
+            MatcherState::Error(_) => None,
+        }
+    }
+
     fn with_inner<T>(&mut self, f: impl FnOnce(&mut MatcherInner) -> Result<T>) -> Result<T> {
         match &mut self.0 {
             MatcherState::Normal(ref mut inner) => {
@@ -65,6 +133,7 @@ impl Matcher {
             for &t in tokens {
                 let bt = inner.parser.consume_token(t)?;
                 ensure!(bt == 0, "unexpected backtracking");
+                inner.tokens_consumed += 1;
             }
             let _ = inner.parser.check_stop()?;
             Ok(())
@@ -72,12 +141,29 @@ impl Matcher {
     }
 
     pub fn rollback(&mut self, num_tokens: usize) -> Result<()> {
-        self.with_inner(|inner| inner.parser.rollback(num_tokens))
+        self.with_inner(|inner| {
+            inner.tokens_consumed = inner.tokens_consumed.saturating_sub(num_tokens);
+            inner.parser.rollback(num_tokens)
+        })
     }
 
     /// Compute which tokens can be consumed in the current state.
+    /// Once `max_tokens` has been reached, this restricts the mask to the
+    /// EOS token (if the grammar can stop here) and puts the parser in
+    /// `StopReason::MaxTokensReached` otherwise, rather than returning the
+    /// grammar's normal (potentially wide) bias set.
     pub fn compute_mask(&mut self) -> Result<SimpleVob> {
-        self.with_inner(|inner| inner.parser.compute_mask())
+        self.with_inner(|inner| {
+            if inner.remaining_tokens() == Some(0) {
+                let trie = inner.parser.token_env.tok_trie();
+                if inner.parser.is_accepting() {
+                    return Ok(trie.singleton_token_set(trie.eos_token()));
+                }
+                inner.parser.force_stop(StopReason::MaxTokensReached);
+                return Ok(trie.alloc_token_set());
+            }
+            inner.parser.compute_mask()
+        })
     }
 
     /// Can the grammar be finished in the current state?
@@ -125,6 +211,7 @@ impl Matcher {
                 }
                 let bt = inner.parser.consume_token(t)?;
                 ensure!(bt == 0, "unexpected backtracking");
+                inner.tokens_consumed += 1;
             }
             let _ = inner.parser.check_stop()?;
             Ok(tokens.len())
@@ -135,6 +222,78 @@ impl Matcher {
         self.with_inner(|inner| inner.parser.validate_tokens_raw(tokens))
     }
 
+    /// Validate a full sequence of tokens against the grammar, but instead of
+    /// failing on the first rejection, record a [`ParseError`] for it and
+    /// resynchronize (per `mode`) so the remainder of `tokens` can still be
+    /// checked. Returns every diagnostic collected, leaving the parser in a
+    /// resynced state; a caller can still inspect `is_accepting`/`stop_reason`
+    /// afterwards.
+    pub fn validate_with_recovery(
+        &mut self,
+        tokens: &[TokenId],
+        mode: RecoveryMode,
+    ) -> Result<Vec<ParseError>> {
+        self.with_inner(|inner| {
+            let mut errors = Vec::new();
+            let mut byte_offset = 0;
+            let mut idx = 0;
+            while idx < tokens.len() {
+                let t = tokens[idx];
+                if inner.parser.validate_token(t)? {
+                    let bt = inner.parser.consume_token(t)?;
+                    ensure!(bt == 0, "unexpected backtracking");
+                    inner.tokens_consumed += 1;
+                    byte_offset += inner.parser.token_env.tok_trie().token(t).len();
+                    idx += 1;
+                    continue;
+                }
+
+                let expected = inner.parser.follow_set_names();
+                errors.push(ParseError {
+                    byte_offset,
+                    rejected_token: Some(t),
+                    expected,
+                });
+                // The rejected token itself is about to be skipped (it's
+                // never re-validated), so its bytes must count towards the
+                // offset of whatever is reported next, same as the
+                // subsequently-skipped tokens below.
+                byte_offset += inner.parser.token_env.tok_trie().token(t).len();
+
+                let (max_skipped_bytes, max_errors) = match mode {
+                    RecoveryMode::Strict => break,
+                    RecoveryMode::Resync {
+                        max_skipped_bytes,
+                        max_errors,
+                    } => (max_skipped_bytes, max_errors),
+                };
+                if errors.len() >= max_errors {
+                    break;
+                }
+
+                // Resync: skip whole rejected tokens forward (re-derived as
+                // bytes) until one is accepted by some currently-open rule,
+                // bounded by the configured byte budget.
+                let mut skipped_bytes = 0;
+                idx += 1;
+                while idx < tokens.len() {
+                    let skip_len = inner.parser.token_env.tok_trie().token(tokens[idx]).len();
+                    if skipped_bytes + skip_len > max_skipped_bytes {
+                        break;
+                    }
+                    if inner.parser.validate_token(tokens[idx])? {
+                        break;
+                    }
+                    skipped_bytes += skip_len;
+                    idx += 1;
+                }
+                byte_offset += skipped_bytes;
+            }
+            let _ = inner.parser.check_stop()?;
+            Ok(errors)
+        })
+    }
+
     pub fn is_error(&self) -> bool {
         matches!(self.0, MatcherState::Error(_))
     }
@@ -146,6 +305,21 @@ impl Matcher {
         }
     }
 
+    /// Serialize the compiled grammar underlying this matcher so it can be
+    /// rehydrated later with `TokenParser::from_compiled_grammar` without
+    /// repeating the optimize/compile step. See `compiled_cache`.
+    pub fn compile_to_bytes(&mut self) -> Result<Vec<u8>> {
+        self.with_inner(|inner| Ok(inner.parser.compile_to_bytes()))
+    }
+
+    /// Render the compiled grammar (`DotKind::Grammar`) or the live Earley
+    /// parse (`DotKind::RunState`) as a Graphviz `digraph`, useful for
+    /// inspecting why a token mask was computed the way it was, or why a
+    /// grammar rejected some input.
+    pub fn state_to_dot(&mut self, kind: DotKind) -> Result<String> {
+        self.with_inner(|inner| Ok(inner.parser.to_dot(kind)))
+    }
+
     pub fn tok_env(&self) -> Result<TokEnv> {
         match &self.0 {
             MatcherState::Normal(inner) => Ok(inner.parser.token_env.clone()),
@@ -1,8 +1,123 @@
-use anyhow::Result;
+use anyhow::{ensure, Result};
+use regex::Regex;
 use std::{collections::HashMap, vec};
 
 use crate::{api::RegexId, grammar_builder::RegexBuilder};
 
+/// A way to split an input string into chunks for [`substring`]'s suffix
+/// automaton, plus how to render one of those chunks back into the final
+/// regex. The default rendering is a literal match on the chunk's exact
+/// text; chunkers that canonicalize text (like [`WhitespaceNormalizing`])
+/// override `to_regex_id` to match a broader pattern instead.
+pub trait Chunker<'a> {
+    fn chunk(&self, input: &'a str) -> Vec<&'a str>;
+
+    fn to_regex_id(&self, builder: &mut RegexBuilder, chunk: &str) -> RegexId {
+        builder.literal(chunk.to_string())
+    }
+}
+
+/// [`Chunker`] wrapping [`chunk_into_chars`].
+pub struct CharChunker;
+
+impl<'a> Chunker<'a> for CharChunker {
+    fn chunk(&self, input: &'a str) -> Vec<&'a str> {
+        chunk_into_chars(input)
+    }
+}
+
+/// [`Chunker`] wrapping [`chunk_into_words`].
+pub struct WordChunker;
+
+impl<'a> Chunker<'a> for WordChunker {
+    fn chunk(&self, input: &'a str) -> Vec<&'a str> {
+        chunk_into_words(input)
+    }
+}
+
+/// Splits input on a user-supplied separator pattern instead of the fixed
+/// char/word boundaries of [`CharChunker`]/[`WordChunker`]. Lets a caller
+/// constrain chunk boundaries to whole lines, sentences, or other
+/// lexer-defined units (e.g. frawk-style tokens) that those two
+/// granularities can't express.
+pub struct RegexDelimited {
+    separator: Regex,
+}
+
+impl RegexDelimited {
+    pub fn new(separator_pattern: &str) -> Result<Self> {
+        Ok(RegexDelimited {
+            separator: Regex::new(separator_pattern)?,
+        })
+    }
+}
+
+impl<'a> Chunker<'a> for RegexDelimited {
+    fn chunk(&self, input: &'a str) -> Vec<&'a str> {
+        let mut chunks = Vec::new();
+        let mut pos = 0;
+        for m in self.separator.find_iter(input) {
+            if m.start() > pos {
+                chunks.push(&input[pos..m.start()]);
+            }
+            if m.end() > m.start() {
+                chunks.push(&input[m.start()..m.end()]);
+            }
+            pos = m.end();
+        }
+        if pos < input.len() {
+            chunks.push(&input[pos..]);
+        }
+        chunks
+    }
+}
+
+/// The canonical chunk [`WhitespaceNormalizing`] substitutes for every
+/// maximal run of whitespace, regardless of that run's original text. Using
+/// one shared representative is what lets a three-space run in the source
+/// and a single space in the model's output land in the same suffix
+/// automaton state.
+const WHITESPACE_CHUNK: &str = " ";
+
+/// Wraps another [`Chunker`] and collapses every maximal whitespace chunk it
+/// produces down to [`WHITESPACE_CHUNK`], which is then rendered as `\s+`
+/// rather than matched literally. Models rarely reproduce a source's exact
+/// spacing/newlines, so without this a quote like `fox   jumps` in the
+/// source would fail to match `fox jumps` in generation.
+pub struct WhitespaceNormalizing<C> {
+    inner: C,
+}
+
+impl<C> WhitespaceNormalizing<C> {
+    pub fn new(inner: C) -> Self {
+        WhitespaceNormalizing { inner }
+    }
+}
+
+impl<'a, C: Chunker<'a>> Chunker<'a> for WhitespaceNormalizing<C> {
+    fn chunk(&self, input: &'a str) -> Vec<&'a str> {
+        self.inner
+            .chunk(input)
+            .into_iter()
+            .map(|c| {
+                if !c.is_empty() && c.chars().all(char::is_whitespace) {
+                    WHITESPACE_CHUNK
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+
+    fn to_regex_id(&self, builder: &mut RegexBuilder, chunk: &str) -> RegexId {
+        if chunk == WHITESPACE_CHUNK {
+            builder.regex("\\s+".to_string())
+        } else {
+            self.inner.to_regex_id(builder, chunk)
+        }
+    }
+}
+
 #[derive(Debug)]
 struct State<'a> {
     len: usize,
@@ -84,8 +199,28 @@ impl<'a> SuffixAutomaton<'a> {
     }
 }
 
-pub fn substring(builder: &mut RegexBuilder, chunks: Vec<&str>) -> Result<RegexId> {
+pub fn substring<'a>(
+    builder: &mut RegexBuilder,
+    chunker: &impl Chunker<'a>,
+    input: &'a str,
+) -> Result<RegexId> {
+    let chunks = chunker.chunk(input);
     let sa = SuffixAutomaton::from_string(chunks);
+    Ok(automaton_to_regex(builder, &sa, |b, c| {
+        chunker.to_regex_id(b, c)
+    }))
+}
+
+/// Walks a built [`SuffixAutomaton`] bottom-up and lowers it into a regex
+/// matching any contiguous substring of whatever it was built from. `render`
+/// turns one chunk's text into the [`RegexId`] matched for that transition;
+/// [`substring`] passes its [`Chunker`] through here, while [`SubstringIndex`]
+/// (which has no `Chunker` of its own) always matches chunks literally.
+fn automaton_to_regex(
+    builder: &mut RegexBuilder,
+    sa: &SuffixAutomaton,
+    render: impl Fn(&mut RegexBuilder, &str) -> RegexId,
+) -> RegexId {
     let mut state_stack = vec![0];
     let mut node_cache: HashMap<usize, RegexId> = HashMap::new();
 
@@ -118,7 +253,7 @@ pub fn substring(builder: &mut RegexBuilder, chunks: Vec<&str>) -> Result<RegexI
             .next
             .keys()
             .map(|c| {
-                let lit = builder.literal(c.to_string());
+                let lit = render(builder, c);
                 builder.concat(vec![lit, node_cache[&state.next[c]]])
             })
             .collect::<Vec<_>>();
@@ -127,7 +262,132 @@ pub fn substring(builder: &mut RegexBuilder, chunks: Vec<&str>) -> Result<RegexI
         node_cache.insert(*state_index, expr);
         state_stack.pop();
     }
-    Ok(node_cache[&0])
+    node_cache[&0]
+}
+
+/// Chunk inserted between documents in a [`SubstringIndex`].
+/// [`chunk_into_chars`]/[`chunk_into_words`] only emit a NUL for source text
+/// that itself contains one, so a match can cross from one document into
+/// the next only by generating a literal NUL at the boundary.
+const DOCUMENT_SENTINEL: &str = "\0";
+
+/// A [`SuffixAutomaton`] built incrementally across one or more documents,
+/// for callers who run many `substring()`-style constraints against the same
+/// corpus (e.g. a fixed set of retrieved passages) and want to pay the O(n)
+/// automaton build and O(states) regex lowering once instead of per prompt.
+///
+/// Construct with [`SubstringIndex::new`], add text with
+/// [`SubstringIndex::push_document`], and compile the current state with
+/// [`SubstringIndex::to_regex`] as many times as needed.
+pub struct SubstringIndex<'a> {
+    sa: SuffixAutomaton<'a>,
+    has_document: bool,
+}
+
+impl<'a> SubstringIndex<'a> {
+    pub fn new() -> Self {
+        SubstringIndex {
+            sa: SuffixAutomaton::new(),
+            has_document: false,
+        }
+    }
+
+    /// Append another document's chunks. If this isn't the first document,
+    /// it is preceded by [`DOCUMENT_SENTINEL`] so matches can't silently
+    /// span the boundary.
+    pub fn push_document(&mut self, chunks: Vec<&'a str>) {
+        if self.has_document {
+            self.sa.extend(DOCUMENT_SENTINEL);
+        }
+        for chunk in chunks {
+            self.sa.extend(chunk);
+        }
+        self.has_document = true;
+    }
+
+    /// Compile the index's current state into a regex matching any
+    /// contiguous substring of any document pushed so far.
+    pub fn to_regex(&self, builder: &mut RegexBuilder) -> RegexId {
+        automaton_to_regex(builder, &self.sa, |b, c| b.literal(c.to_string()))
+    }
+}
+
+impl<'a> Default for SubstringIndex<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Like `substring()`, but the returned regex accepts any string within
+/// Levenshtein distance `k` of some contiguous substring of `chunks`. Useful
+/// for grounded/RAG decoding where the model should quote the source but may
+/// legitimately drift by a few characters.
+///
+/// Only char chunks (as produced by `chunk_into_chars`) are supported: word
+/// chunks don't have a well-defined "any character" class to substitute or
+/// insert, so they are rejected.
+pub fn substring_fuzzy(builder: &mut RegexBuilder, chunks: Vec<&str>, k: usize) -> Result<RegexId> {
+    for c in &chunks {
+        ensure!(
+            c.chars().count() == 1,
+            "substring_fuzzy only supports chunk_into_chars input, got multi-char chunk {c:?}"
+        );
+    }
+
+    let sa = SuffixAutomaton::from_string(chunks);
+    let empty = builder.literal("".to_string());
+    let any_char = builder.regex(".".to_string());
+
+    // Cache keyed by (state_index, remaining_budget) rather than just
+    // state_index, since how much edit budget is left changes what's
+    // reachable from here. This bounds the blowup to O(states * (k+1)).
+    let mut cache: HashMap<(usize, usize), RegexId> = HashMap::new();
+    Ok(fuzzy_expr(builder, &sa, 0, k, &mut cache, empty, any_char))
+}
+
+fn fuzzy_expr(
+    builder: &mut RegexBuilder,
+    sa: &SuffixAutomaton,
+    state_index: usize,
+    budget: usize,
+    cache: &mut HashMap<(usize, usize), RegexId>,
+    empty: RegexId,
+    any_char: RegexId,
+) -> RegexId {
+    if let Some(&cached) = cache.get(&(state_index, budget)) {
+        return cached;
+    }
+
+    let state = &sa.states[state_index];
+    // A match can always end here (the suffix automaton's empty-suffix
+    // option), same as in the exact `substring` builder.
+    let mut options = vec![empty];
+
+    for (&c, &next) in state.next.iter() {
+        // (a) exact transition on `c`, no budget spent.
+        let follow = fuzzy_expr(builder, sa, next, budget, cache, empty, any_char);
+        let lit = builder.literal(c.to_string());
+        options.push(builder.concat(vec![lit, follow]));
+
+        if budget > 0 {
+            // (b) deletion: consume `c` from the source without emitting it.
+            options.push(fuzzy_expr(builder, sa, next, budget - 1, cache, empty, any_char));
+            // (d) substitution: emit any char instead of `c`.
+            let follow = fuzzy_expr(builder, sa, next, budget - 1, cache, empty, any_char);
+            options.push(builder.concat(vec![any_char, follow]));
+        }
+    }
+
+    if budget > 0 {
+        // (c) insertion: emit an extra char not present in the source,
+        // staying at the same automaton state.
+        let follow = fuzzy_expr(builder, sa, state_index, budget - 1, cache, empty, any_char);
+        options.push(builder.concat(vec![any_char, follow]));
+    }
+
+    let expr = builder.or(options);
+    cache.insert((state_index, budget), expr);
+    expr
 }
 
 pub fn chunk_into_chars(input: &str) -> Vec<&str> {
@@ -191,7 +451,10 @@ mod test {
         grammar_builder::RegexBuilder,
     };
 
-    use super::{chunk_into_chars, chunk_into_words, substring};
+    use super::{
+        chunk_into_chars, chunk_into_words, substring, substring_fuzzy, CharChunker,
+        RegexDelimited, SubstringIndex, WhitespaceNormalizing, WordChunker,
+    };
 
     fn to_regex(mut builder: RegexBuilder, expr: RegexId) -> Regex {
         let limits = ParserLimits::default();
@@ -273,7 +536,8 @@ mod test {
         let mut builder = RegexBuilder::new();
         let expr = substring(
             &mut builder,
-            chunk_into_chars("The quick brown fox jumps over the lazy dog."),
+            &CharChunker,
+            "The quick brown fox jumps over the lazy dog.",
         )
         .unwrap();
         let regex = to_regex(builder, expr);
@@ -290,12 +554,36 @@ mod test {
         assert_eq!(regex.clone().is_match("brown fx"), false);
     }
 
+    #[test]
+    fn test_substring_fuzzy() {
+        let mut builder = RegexBuilder::new();
+        let expr = substring_fuzzy(&mut builder, chunk_into_chars("brown fox"), 1).unwrap();
+        let regex = to_regex(builder, expr);
+        // exact substring still matches
+        assert_eq!(regex.clone().is_match("brown fox"), true);
+        // substitution: one character swapped
+        assert_eq!(regex.clone().is_match("brown fax"), true);
+        // deletion: one character missing
+        assert_eq!(regex.clone().is_match("brwn fox"), true);
+        // insertion: one extra character
+        assert_eq!(regex.clone().is_match("brownn fox"), true);
+        // two edits is outside the k=1 budget
+        assert_eq!(regex.clone().is_match("brwn fax"), false);
+    }
+
+    #[test]
+    fn test_substring_fuzzy_rejects_word_chunks() {
+        let mut builder = RegexBuilder::new();
+        assert!(substring_fuzzy(&mut builder, chunk_into_words("brown fox"), 1).is_err());
+    }
+
     #[test]
     fn test_substring_chars_unicode() {
         let mut builder = RegexBuilder::new();
         let expr = substring(
             &mut builder,
-            chunk_into_chars("빠른 갈색 여우가 게으른 개를 뛰어넘었다."),
+            &CharChunker,
+            "빠른 갈색 여우가 게으른 개를 뛰어넘었다.",
         )
         .unwrap();
         let regex = to_regex(builder, expr);
@@ -317,7 +605,8 @@ mod test {
         let mut builder = RegexBuilder::new();
         let expr = substring(
             &mut builder,
-            chunk_into_words("The quick brown fox jumps over the lazy dog."),
+            &WordChunker,
+            "The quick brown fox jumps over the lazy dog.",
         )
         .unwrap();
         let regex = to_regex(builder, expr);
@@ -339,7 +628,8 @@ mod test {
         let mut builder = RegexBuilder::new();
         let expr = substring(
             &mut builder,
-            chunk_into_words("빠른 갈색 여우가 게으른 개를 뛰어넘었다."),
+            &WordChunker,
+            "빠른 갈색 여우가 게으른 개를 뛰어넘었다.",
         )
         .unwrap();
         let regex = to_regex(builder, expr);
@@ -355,4 +645,47 @@ mod test {
         assert_eq!(regex.clone().is_match("뛰어넘었다."), true);
         assert_eq!(regex.clone().is_match("갈색 여가"), false);
     }
+
+    #[test]
+    fn test_substring_regex_delimited() {
+        let mut builder = RegexBuilder::new();
+        let chunker = RegexDelimited::new(r"\n").unwrap();
+        let expr = substring(&mut builder, &chunker, "line one\nline two\nline three").unwrap();
+        let regex = to_regex(builder, expr);
+        assert_eq!(regex.clone().is_match("line one\nline two\nline three"), true);
+        assert_eq!(regex.clone().is_match("line two\nline three"), true);
+        // a partial line isn't a whole chunk
+        assert_eq!(regex.clone().is_match("ine two"), false);
+    }
+
+    #[test]
+    fn test_substring_whitespace_normalizing() {
+        let mut builder = RegexBuilder::new();
+        let chunker = WhitespaceNormalizing::new(WordChunker);
+        let expr = substring(&mut builder, &chunker, "the fox   jumps\nover the dog").unwrap();
+        let regex = to_regex(builder, expr);
+        // exact source spacing still matches
+        assert_eq!(regex.clone().is_match("fox   jumps"), true);
+        // normalized/re-spaced generation also matches
+        assert_eq!(regex.clone().is_match("fox jumps"), true);
+        assert_eq!(regex.clone().is_match("fox\njumps"), true);
+        // but the words themselves still have to line up
+        assert_eq!(regex.clone().is_match("fox hops"), false);
+    }
+
+    #[test]
+    fn test_substring_index_multi_document() {
+        let mut index = SubstringIndex::new();
+        index.push_document(chunk_into_words("brown fox"));
+        index.push_document(chunk_into_words("lazy dog"));
+
+        let mut builder = RegexBuilder::new();
+        let expr = index.to_regex(&mut builder);
+        let regex = to_regex(builder, expr);
+
+        assert_eq!(regex.clone().is_match("brown fox"), true);
+        assert_eq!(regex.clone().is_match("lazy dog"), true);
+        // must not be able to splice across the document boundary
+        assert_eq!(regex.clone().is_match("fox lazy"), false);
+    }
 }
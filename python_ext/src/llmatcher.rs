@@ -195,6 +195,41 @@ impl LLMatcher {
         }
     }
 
+    /// Compile `grammar` (same argument shape as the constructor) and return
+    /// an opaque, versioned byte blob that `from_cache()` can later turn
+    /// back into a ready-to-run `LLMatcher` without repeating the
+    /// optimize/compile step. The blob embeds a fingerprint of `tokenizer`
+    /// and is rejected by `from_cache()` if used with a different one.
+    #[staticmethod]
+    #[pyo3(signature = (tokenizer, grammar))]
+    fn compile_grammar_to_cache(
+        tokenizer: &LLTokenizer,
+        grammar: Bound<'_, PyAny>,
+        py: Python<'_>,
+    ) -> PyResult<Cow<'static, [u8]>> {
+        let mut matcher = Self::py_new(tokenizer, grammar, None, py)?;
+        Ok(Cow::Owned(matcher.inner.compile_to_bytes().map_err(val_error)?))
+    }
+
+    /// Rehydrate an `LLMatcher` from a cache blob produced by
+    /// `compile_grammar_to_cache()`, skipping grammar compilation.
+    #[staticmethod]
+    fn from_cache(tokenizer: &LLTokenizer, bytes: &[u8]) -> PyResult<Self> {
+        let fact = tokenizer.factory();
+        let inner = TokenParser::from_compiled_grammar(
+            fact.tok_env().clone(),
+            bytes,
+            Logger::new(0, 1),
+            ParserLimits::default(),
+        )
+        .map_err(val_error)?;
+        let inner = Matcher::new(Ok(inner));
+        Ok(LLMatcher {
+            inner,
+            tok_env: fact.tok_env().clone(),
+        })
+    }
+
     #[staticmethod]
     fn grammar_from_lark(lark: String) -> String {
         // lark can be passed directly
@@ -225,6 +260,17 @@ impl LLMatcher {
         self.inner.stop_reason().to_string()
     }
 
+    #[pyo3(signature = (max_tokens=None))]
+    fn set_max_tokens(&mut self, max_tokens: Option<usize>) {
+        self.inner.set_max_tokens(max_tokens);
+    }
+
+    /// Remaining token budget before `StopReason::MaxTokensReached` kicks in,
+    /// or `None` if no limit is set (or the matcher is in an error state).
+    fn remaining_tokens(&self) -> Option<usize> {
+        self.inner.remaining_tokens()
+    }
+
     fn validate_tokens(&mut self, tokens: Vec<TokenId>) -> usize {
         self.inner.validate_tokens(&tokens).unwrap_or_else(|_| {
             let eos = self.tok_env.tok_trie().eos_token();